@@ -1,61 +1,106 @@
 
 use bitcoin::{
-    consensus::{deserialize_partial, serialize}, p2p::{message::{RawNetworkMessage, self, NetworkMessage}, message_network::VersionMessage, ServiceFlags}, Network
+    consensus::{deserialize_partial, serialize}, p2p::{address::{AddrV2, AddrV2Message}, message::{RawNetworkMessage, self, NetworkMessage}, message_network::VersionMessage, ServiceFlags}, Network
 };
 use bytes::{Buf, BytesMut};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{tcp::OwnedReadHalf, TcpStream},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
     select, signal,
     sync::{
         broadcast,
         mpsc::{self, error::SendError, UnboundedSender},
     },
+    task::JoinHandle,
     try_join,
 };
 
 use std::{
     net::{IpAddr, Ipv4Addr, SocketAddr},
     str::FromStr,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use crate::types;
 use types::*;
 
-
-const EXPECTED_HANDSHAKE_MESSAGES: usize = 4;
 const TIMEOUT_MILLISEC: u64 = 1000;
+const DRAIN_GRACE_MILLISEC: u64 = 250;
+
+/// Broadcast over `shutdown_tx` to move the handshake towards completion
+/// without dropping in-flight work. `Draining` tells every task to stop
+/// taking on new work and flush what it already has queued; `Close` is only
+/// sent once that flush is done (or a grace period elapses), and is the
+/// writer's cue to actually close the socket.
+#[derive(Clone, Copy, Debug)]
+enum ShutdownPhase {
+    Draining,
+    Close,
+}
+
+/// State shared by the event chain, writer and reader tasks of a single
+/// handshake: who the peer is, whether we're crawling, and the broadcast
+/// handle used to coordinate shutdown between them.
+struct SharedState {
+    peer_id: String,
+    crawl: bool,
+    network: Network,
+    shutdown_tx: broadcast::Sender<ShutdownPhase>,
+    // Timestamp of the most recently received `Ping`, used to record the
+    // interval between the peer's keep-alive pings as an `Event` data pair.
+    last_ping_at: Mutex<Option<Instant>>,
+}
+
+impl SharedState {
+    fn new(
+        peer_id: String,
+        crawl: bool,
+        network: Network,
+        shutdown_tx: broadcast::Sender<ShutdownPhase>,
+    ) -> Self {
+        SharedState {
+            peer_id,
+            crawl,
+            network,
+            shutdown_tx,
+            last_ping_at: Mutex::new(None),
+        }
+    }
+}
 
 pub async fn perform_btc_handshake(params: HandshakeParams) -> Result<EventChain, HSError> {
-    // Setup shutdown broadcast channels
-    let (shutdown_tx, _) = broadcast::channel::<usize>(1);
+    perform_btc_handshake_ex(params, false, Duration::from_millis(TIMEOUT_MILLISEC)).await
+}
+
+/// Same as `perform_btc_handshake`, but when `crawl` is set the connection is
+/// kept open past `verack` (a `getaddr` is sent and `addr`/`addrv2` replies are
+/// recorded) instead of shutting down as soon as the handshake completes.
+/// `dwell` bounds how long the connection is allowed to stay open, counted
+/// from when `version` is sent (so the handshake itself uses part of it),
+/// before shutdown begins; callers that expect a post-handshake
+/// reply (e.g. the crawler waiting on `addr`/`addrv2`) should pass something
+/// longer than the plain handshake's connect timeout.
+pub async fn perform_btc_handshake_ex(
+    params: HandshakeParams,
+    crawl: bool,
+    dwell: Duration,
+) -> Result<EventChain, HSError> {
+    // Setup shutdown broadcast channel and the state shared by every task.
+    let (shutdown_tx, _) = broadcast::channel::<ShutdownPhase>(2);
+    let state = Arc::new(SharedState::new(
+        params.address.clone(),
+        crawl,
+        params.network,
+        shutdown_tx,
+    ));
+    // Each drainable task (writer, reader) reports in here once it has
+    // flushed its queue, so the outer loop knows when it's safe to close.
+    let (drain_done_tx, mut drain_done_rx) = mpsc::channel::<()>(2);
 
     // Spawn the event chain task.
-    let (ev_tx, mut ev_rx) = mpsc::unbounded_channel();
-    let mut ev_shutdown_rx = shutdown_tx.subscribe();
-    let ev_shutdown_tx = shutdown_tx.clone();
-    let event_chain_id = params.address.clone();
-    let event_chain_handle = tokio::spawn(async move {
-        let mut event_chain = EventChain::new(event_chain_id);
-        loop {
-            select! {
-                Some(ev) = ev_rx.recv() => {
-                    event_chain.add(ev);
-                }
-                recv_res = ev_shutdown_rx.recv() => {
-                    return match recv_res {
-                        Ok(_) => Ok(event_chain),
-                        Err(err) => Err(HSError::from(err)),
-                    }
-                }
-            }
-            if event_chain.len() == EXPECTED_HANDSHAKE_MESSAGES {
-                event_chain.mark_as_complete();
-                ev_shutdown_tx.send(1)?;
-            }
-        }
-    });
+    let (ev_tx, ev_rx) = mpsc::unbounded_channel();
+    let event_chain_handle = EventChainTask::new(ev_rx, state.clone()).spawn();
 
     // Stablish TCP connection with timeout.
     let stream = tokio::time::timeout(
@@ -64,86 +109,53 @@ pub async fn perform_btc_handshake(params: HandshakeParams) -> Result<EventChain
     )
     .await??;
 
-    let (rx_stream, mut tx_stream) = stream.into_split();
+    let (rx_stream, tx_stream) = stream.into_split();
 
     // Spawn the message writer task. This will take care of serialize all messages write to the socket.
-    let (msg_tx, mut msg_rx) = mpsc::unbounded_channel::<RawNetworkMessage>();
-    let msg_writer_ev_tx = ev_tx.clone();
-    let mut msg_writer_shutdown_rx = shutdown_tx.subscribe();
-    let msg_writer_handle = tokio::spawn(async move {
-        loop {
-            select! {
-                Some(msg) = msg_rx.recv() => {
-                    let msg_type = msg.cmd().to_string();
-                    let data = serialize(&msg);
-                    tx_stream.write_all(data.as_slice()).await?;
-                    msg_writer_ev_tx.send(Event::new(msg_type, EventDirection::OUT))?;
-                }
-                result = msg_writer_shutdown_rx.recv() => {
-                    tx_stream.shutdown().await?;
-                    return match result {
-                        Ok(_) => Ok(()),
-                        Err(err) => Err(HSError::from(err)),
-                    }
-                }
-            }
-        }
-    });
-
-    // Spawn the message reader task
-    let mut msg_reader_shutdown_rx = shutdown_tx.subscribe();
-    let msg_reader_msg_tx = msg_tx.clone();
-    let msg_reader_handle = tokio::spawn(async move {
-        // A complete handshake is about 342 bytes. We allocate much more so we don't need
-        // to do more allocations.
-        let mut msg_reader = MessageReader::new(rx_stream, 1024);
-        let mut handles = Vec::new();
-        loop {
-            select! {
-                message_res = msg_reader.read_message() => {
-                    match message_res {
-                        Ok(opt_res) => {
-                            if let Some(msg) = opt_res {
-                                let handle = tokio::spawn(handle_message(msg, msg_reader_msg_tx.clone(), ev_tx.clone()));
-                                handles.push(handle);
-                            }
-                         },
-                        Err(err) => return Err(err),
-                    }
-                },
-                result = msg_reader_shutdown_rx.recv() => {
-                   return match result {
-                     Ok(_) => {
-                       // Ensure all message handles succeeded before ending.
-                       futures::future::try_join_all(handles).await?;
-                       Ok(())
-                     },
-                     Err(err) => Err(HSError::from(err)),
-                    }
-                }
-            }
-        }
-    });
+    let (msg_tx, msg_rx) = mpsc::unbounded_channel::<RawNetworkMessage>();
+    let msg_writer_handle = MessageWriterTask::new(
+        tx_stream,
+        msg_rx,
+        ev_tx.clone(),
+        drain_done_tx.clone(),
+        &state.shutdown_tx,
+    )
+    .spawn();
+
+    // Spawn the message reader task. A complete handshake is about 342 bytes;
+    // we allocate much more so we don't need more allocations.
+    let msg_reader_handle =
+        MessageReader::new(rx_stream, 1024, msg_tx.clone(), ev_tx, drain_done_tx, state.clone())
+            .spawn();
 
     // Start the handshake by sending the first VERSION message
-    let version_message = version_message(params.address, params.user_agent);
+    let version_message = version_message(params.address, params.user_agent, params.network);
     msg_tx.send(version_message)?;
 
     // Wait for external shutdown signals ctr+c ...
-    let mut ext_shutdown_shutdown_rx = shutdown_tx.subscribe();
+    let mut ext_shutdown_shutdown_rx = state.shutdown_tx.subscribe();
     select! {
-        _ = tokio::time::sleep(Duration::from_millis(TIMEOUT_MILLISEC)) => {
-            shutdown_tx.send(1)?;
+        _ = tokio::time::sleep(dwell) => {
+            state.shutdown_tx.send(ShutdownPhase::Draining)?;
         }
         val = signal::ctrl_c() => {
             if val.is_ok(){
-                shutdown_tx.send(1)?;
+                state.shutdown_tx.send(ShutdownPhase::Draining)?;
             }
         }
         // Break this select! once an internal shutdown is invoked from any of the subs systems.
         _val = ext_shutdown_shutdown_rx.recv()=>{}
     }
 
+    // Give the writer and reader a bounded grace period to report they've
+    // drained their queues before we force the close.
+    let _ = tokio::time::timeout(Duration::from_millis(DRAIN_GRACE_MILLISEC), async {
+        drain_done_rx.recv().await;
+        drain_done_rx.recv().await;
+    })
+    .await;
+    state.shutdown_tx.send(ShutdownPhase::Close)?;
+
     let (event_chain_res, message_writer_res, msg_reader_res) =
         try_join!(event_chain_handle, msg_writer_handle, msg_reader_handle)?;
     // Check no errors happened in message reader and writer.
@@ -153,16 +165,167 @@ pub async fn perform_btc_handshake(params: HandshakeParams) -> Result<EventChain
     event_chain_res
 }
 
+/// Checks whether the four handshake messages (`version`/`verack` in both
+/// directions) have all been observed, regardless of how many other events
+/// (e.g. crawl-mode `addr` replies) are also in the chain.
+fn is_handshake_complete(chain: &EventChain) -> bool {
+    let mut saw_version_out = false;
+    let mut saw_version_in = false;
+    let mut saw_verack_in = false;
+    let mut saw_verack_out = false;
+
+    for i in 0..chain.len() {
+        if let Some(ev) = chain.get(i) {
+            match (ev.name(), ev.direction()) {
+                ("version", EventDirection::OUT) => saw_version_out = true,
+                ("version", EventDirection::IN) => saw_version_in = true,
+                ("verack", EventDirection::IN) => saw_verack_in = true,
+                ("verack", EventDirection::OUT) => saw_verack_out = true,
+                _ => {}
+            }
+        }
+    }
+
+    saw_version_out && saw_version_in && saw_verack_in && saw_verack_out
+}
+
+/// Collects every `Event` published during the handshake into an `EventChain`,
+/// and decides when the handshake is done (see `is_handshake_complete`).
+struct EventChainTask {
+    ev_rx: mpsc::UnboundedReceiver<Event>,
+    shutdown_rx: broadcast::Receiver<ShutdownPhase>,
+    state: Arc<SharedState>,
+}
+
+impl EventChainTask {
+    fn new(ev_rx: mpsc::UnboundedReceiver<Event>, state: Arc<SharedState>) -> Self {
+        let shutdown_rx = state.shutdown_tx.subscribe();
+        EventChainTask {
+            ev_rx,
+            shutdown_rx,
+            state,
+        }
+    }
+
+    fn spawn(mut self) -> JoinHandle<Result<EventChain, HSError>> {
+        tokio::spawn(async move { self.run().await })
+    }
+
+    async fn run(&mut self) -> Result<EventChain, HSError> {
+        let mut event_chain = EventChain::new(self.state.peer_id.clone());
+        loop {
+            select! {
+                Some(ev) = self.ev_rx.recv() => {
+                    event_chain.add(ev);
+                }
+                recv_res = self.shutdown_rx.recv() => {
+                    match recv_res {
+                        Ok(ShutdownPhase::Draining) => continue,
+                        Ok(ShutdownPhase::Close) => return Ok(event_chain),
+                        Err(err) => return Err(HSError::from(err)),
+                    }
+                }
+            }
+            if !event_chain.is_complete() && is_handshake_complete(&event_chain) {
+                event_chain.mark_as_complete();
+                if !self.state.crawl {
+                    self.state.shutdown_tx.send(ShutdownPhase::Draining)?;
+                }
+            }
+        }
+    }
+}
+
+/// Serializes and writes every `RawNetworkMessage` it's handed to the socket.
+struct MessageWriterTask<W> {
+    tx_stream: W,
+    msg_rx: mpsc::UnboundedReceiver<RawNetworkMessage>,
+    ev_tx: mpsc::UnboundedSender<Event>,
+    shutdown_rx: broadcast::Receiver<ShutdownPhase>,
+    drain_done_tx: mpsc::Sender<()>,
+}
+
+impl<W> MessageWriterTask<W>
+where
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    fn new(
+        tx_stream: W,
+        msg_rx: mpsc::UnboundedReceiver<RawNetworkMessage>,
+        ev_tx: mpsc::UnboundedSender<Event>,
+        drain_done_tx: mpsc::Sender<()>,
+        shutdown_tx: &broadcast::Sender<ShutdownPhase>,
+    ) -> Self {
+        let shutdown_rx = shutdown_tx.subscribe();
+        MessageWriterTask {
+            tx_stream,
+            msg_rx,
+            ev_tx,
+            shutdown_rx,
+            drain_done_tx,
+        }
+    }
+
+    fn spawn(mut self) -> JoinHandle<Result<(), HSError>> {
+        tokio::spawn(async move { self.run().await })
+    }
+
+    async fn write(&mut self, msg: RawNetworkMessage) -> Result<(), HSError> {
+        let msg_type = msg.cmd().to_string();
+        let data = serialize(&msg);
+        self.tx_stream.write_all(data.as_slice()).await?;
+        self.ev_tx.send(Event::new(msg_type, EventDirection::OUT))?;
+        Ok(())
+    }
+
+    async fn run(&mut self) -> Result<(), HSError> {
+        loop {
+            select! {
+                Some(msg) = self.msg_rx.recv() => {
+                    self.write(msg).await?;
+                }
+                result = self.shutdown_rx.recv() => {
+                    match result {
+                        Ok(ShutdownPhase::Draining) => {
+                            // Flush whatever is already queued before reporting drained.
+                            while let Ok(msg) = self.msg_rx.try_recv() {
+                                self.write(msg).await?;
+                            }
+                            let _ = self.drain_done_tx.send(()).await;
+                        }
+                        Ok(ShutdownPhase::Close) => {
+                            // A handle_message task the reader was still awaiting during
+                            // Draining can enqueue a message (e.g. a pong, or a crawl-mode
+                            // getaddr) after we last flushed but before Close arrives. Flush
+                            // once more here so that message still reaches the socket.
+                            while let Ok(msg) = self.msg_rx.try_recv() {
+                                self.write(msg).await?;
+                            }
+                            self.tx_stream.shutdown().await?;
+                            return Ok(());
+                        }
+                        Err(err) => return Err(HSError::from(err)),
+                    }
+                }
+            }
+        }
+    }
+}
+
 async fn handle_message(
     message: RawNetworkMessage,
     msg_writer: UnboundedSender<RawNetworkMessage>,
     event_publisher: UnboundedSender<Event>,
+    state: Arc<SharedState>,
 ) -> Result<(), HSError> {
     let msg_type = message.cmd().to_string();
     match message.payload() {
         message::NetworkMessage::Verack => {
             let event = Event::new(msg_type, EventDirection::IN);
             event_publisher.send(event)?;
+            if state.crawl {
+                msg_writer.send(getaddr_message(state.network))?;
+            }
             Ok(())
         }
         message::NetworkMessage::Version(v) => {
@@ -170,7 +333,55 @@ async fn handle_message(
             event.set_pair("vers".to_string(), v.version.to_string());
             event.set_pair("user-agent".to_string(), v.user_agent.clone());
             event_publisher.send(event)?;
-            msg_writer.send(verack_message())?;
+            msg_writer.send(verack_message(state.network))?;
+            Ok(())
+        }
+        message::NetworkMessage::Ping(nonce) => {
+            let mut event = Event::new(msg_type, EventDirection::IN);
+            event.set_pair("nonce".to_string(), nonce.to_string());
+            // We never initiate a ping ourselves, so there's no round trip of
+            // our own to time; record the interval between the peer's
+            // successive keep-alive pings instead.
+            let now = Instant::now();
+            let mut last_ping_at = state.last_ping_at.lock().unwrap();
+            if let Some(previous) = *last_ping_at {
+                event.set_pair(
+                    "since_last_ping_ms".to_string(),
+                    now.duration_since(previous).as_millis().to_string(),
+                );
+            }
+            *last_ping_at = Some(now);
+            drop(last_ping_at);
+            event_publisher.send(event)?;
+            msg_writer.send(pong_message(*nonce, state.network))?;
+            Ok(())
+        }
+        message::NetworkMessage::Pong(nonce) => {
+            let mut event = Event::new(msg_type, EventDirection::IN);
+            event.set_pair("nonce".to_string(), nonce.to_string());
+            event_publisher.send(event)?;
+            Ok(())
+        }
+        message::NetworkMessage::Addr(addresses) => {
+            for (_, addr) in addresses {
+                if let Ok(socket_addr) = addr.socket_addr() {
+                    let mut event = Event::new(msg_type.clone(), EventDirection::IN);
+                    event.set_pair("address".to_string(), socket_addr.to_string());
+                    event.set_pair("source-peer".to_string(), state.peer_id.clone());
+                    event_publisher.send(event)?;
+                }
+            }
+            Ok(())
+        }
+        message::NetworkMessage::AddrV2(addresses) => {
+            for entry in addresses {
+                if let Some(socket_addr) = addr_v2_socket(entry) {
+                    let mut event = Event::new(msg_type.clone(), EventDirection::IN);
+                    event.set_pair("address".to_string(), socket_addr.to_string());
+                    event.set_pair("source-peer".to_string(), state.peer_id.clone());
+                    event_publisher.send(event)?;
+                }
+            }
             Ok(())
         }
         _ => {
@@ -183,46 +394,153 @@ async fn handle_message(
     }
 }
 
-struct MessageReader {
-    stream: OwnedReadHalf,
+fn addr_v2_socket(entry: &AddrV2Message) -> Option<SocketAddr> {
+    match entry.addr {
+        AddrV2::Ipv4(ip) => Some(SocketAddr::new(IpAddr::V4(ip), entry.port)),
+        AddrV2::Ipv6(ip) => Some(SocketAddr::new(IpAddr::V6(ip), entry.port)),
+        _ => None,
+    }
+}
+
+/// Reads `RawNetworkMessage`s off the socket, and once it owns its channels
+/// (see `spawn`) runs the full read loop: dispatching each message to
+/// `handle_message` and draining those handles before shutting down.
+struct MessageReader<R> {
+    stream: R,
     buffer: BytesMut,
+    msg_writer: mpsc::UnboundedSender<RawNetworkMessage>,
+    ev_tx: mpsc::UnboundedSender<Event>,
+    shutdown_rx: broadcast::Receiver<ShutdownPhase>,
+    drain_done_tx: mpsc::Sender<()>,
+    state: Arc<SharedState>,
 }
 
-impl MessageReader {
-    pub fn new(stream: OwnedReadHalf, buff_size: usize) -> MessageReader {
+impl<R> MessageReader<R>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    // A complete handshake is about 342 bytes; `buff_size` is chosen well above
+    // that so we don't need more allocations for the handshake itself.
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        stream: R,
+        buff_size: usize,
+        msg_writer: mpsc::UnboundedSender<RawNetworkMessage>,
+        ev_tx: mpsc::UnboundedSender<Event>,
+        drain_done_tx: mpsc::Sender<()>,
+        state: Arc<SharedState>,
+    ) -> Self {
+        let shutdown_rx = state.shutdown_tx.subscribe();
         MessageReader {
             stream,
             buffer: BytesMut::with_capacity(buff_size),
+            msg_writer,
+            ev_tx,
+            shutdown_rx,
+            drain_done_tx,
+            state,
         }
     }
-    pub async fn read_message(&mut self) -> Result<Option<RawNetworkMessage>, HSError> {
+
+    fn spawn(mut self) -> JoinHandle<Result<(), HSError>> {
+        tokio::spawn(async move { self.run().await })
+    }
+
+    async fn run(&mut self) -> Result<(), HSError> {
+        let mut handles = Vec::new();
+        let mut draining = false;
         loop {
-            if let Ok((message, count)) = deserialize_partial::<RawNetworkMessage>(&self.buffer) {
-                self.buffer.advance(count);
-                return Ok(Some(message));
+            select! {
+                message_res = read_message(&mut self.stream, &mut self.buffer), if !draining => {
+                    match message_res {
+                        Ok(Some(msg)) => {
+                            if *msg.magic() != self.state.network.magic() {
+                                return Err(HSError {
+                                    err_message: format!(
+                                        "magic mismatch: expected {} for {:?}, got {}",
+                                        self.state.network.magic(),
+                                        self.state.network,
+                                        msg.magic()
+                                    ),
+                                });
+                            }
+                            let handle = tokio::spawn(handle_message(
+                                msg,
+                                self.msg_writer.clone(),
+                                self.ev_tx.clone(),
+                                self.state.clone(),
+                            ));
+                            handles.push(handle);
+                        },
+                        Ok(None) => {}
+                        Err(err) => return Err(err),
+                    }
+                },
+                result = self.shutdown_rx.recv() => {
+                   match result {
+                     Ok(ShutdownPhase::Draining) => {
+                       draining = true;
+                       // Wait on every handle_message task already pushed before reporting drained.
+                       futures::future::try_join_all(handles.drain(..)).await?;
+                       let _ = self.drain_done_tx.send(()).await;
+                     }
+                     Ok(ShutdownPhase::Close) => return Ok(()),
+                     Err(err) => return Err(HSError::from(err)),
+                    }
+                }
             }
+        }
+    }
+}
 
-            if 0 == self.stream.read_buf(&mut self.buffer).await? {
-                if self.buffer.is_empty() {
-                    return Ok(None);
-                } else {
-                    return Err(HSError {
-                        err_message: "connection reset by peer".into(),
-                    });
-                }
+/// Pulls a complete `RawNetworkMessage` out of `buffer`, reading more off
+/// `stream` as needed. Kept as a free function (rather than a method taking
+/// `&mut self`) so the reader's shutdown channel can be borrowed
+/// independently of `stream`/`buffer` inside `MessageReader::run`'s `select!`.
+async fn read_message<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    buffer: &mut BytesMut,
+) -> Result<Option<RawNetworkMessage>, HSError> {
+    loop {
+        if let Ok((message, count)) = deserialize_partial::<RawNetworkMessage>(buffer) {
+            buffer.advance(count);
+            return Ok(Some(message));
+        }
+
+        if 0 == stream.read_buf(buffer).await? {
+            if buffer.is_empty() {
+                return Ok(None);
+            } else {
+                return Err(HSError {
+                    err_message: "connection reset by peer".into(),
+                });
             }
         }
     }
 }
 
-pub fn verack_message() -> RawNetworkMessage {
+pub fn verack_message(network: Network) -> RawNetworkMessage {
     RawNetworkMessage::new(
-        Network::Bitcoin.magic(),
+        network.magic(),
          NetworkMessage::Verack,
     )
 }
 
-pub fn version_message(dest_socket: String, user_agent: String) -> RawNetworkMessage {
+pub fn getaddr_message(network: Network) -> RawNetworkMessage {
+    RawNetworkMessage::new(
+        network.magic(),
+        NetworkMessage::GetAddr,
+    )
+}
+
+pub fn pong_message(nonce: u64, network: Network) -> RawNetworkMessage {
+    RawNetworkMessage::new(
+        network.magic(),
+        NetworkMessage::Pong(nonce),
+    )
+}
+
+pub fn version_message(dest_socket: String, user_agent: String, network: Network) -> RawNetworkMessage {
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
@@ -242,9 +560,9 @@ pub fn version_message(dest_socket: String, user_agent: String) -> RawNetworkMes
     );
 
     RawNetworkMessage::new(
-         Network::Bitcoin.magic(),
+         network.magic(),
          NetworkMessage::Version(btc_version))
-    
+
 }
 
 impl From<SendError<RawNetworkMessage>> for HSError {
@@ -254,3 +572,100 @@ impl From<SendError<RawNetworkMessage>> for HSError {
         }
     }
 }
+
+impl From<broadcast::error::SendError<ShutdownPhase>> for HSError {
+    fn from(send_err: broadcast::error::SendError<ShutdownPhase>) -> Self {
+        HSError {
+            err_message: send_err.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::duplex;
+
+    fn event(name: &str, direction: EventDirection) -> Event {
+        Event::new(name.to_string(), direction)
+    }
+
+    #[test]
+    fn handshake_incomplete_without_all_four_messages() {
+        let mut chain = EventChain::new("test-peer".to_string());
+        chain.add(event("version", EventDirection::OUT));
+        chain.add(event("version", EventDirection::IN));
+        assert!(!is_handshake_complete(&chain));
+    }
+
+    #[test]
+    fn handshake_complete_once_all_four_messages_seen_in_any_order() {
+        let mut chain = EventChain::new("test-peer".to_string());
+        chain.add(event("verack", EventDirection::IN));
+        chain.add(event("version", EventDirection::OUT));
+        chain.add(event("addr", EventDirection::IN)); // unrelated events don't matter
+        chain.add(event("version", EventDirection::IN));
+        chain.add(event("verack", EventDirection::OUT));
+        assert!(is_handshake_complete(&chain));
+    }
+
+    fn test_state(crawl: bool) -> Arc<SharedState> {
+        let (shutdown_tx, _) = broadcast::channel::<ShutdownPhase>(2);
+        Arc::new(SharedState::new(
+            "127.0.0.1:8333".to_string(),
+            crawl,
+            Network::Bitcoin,
+            shutdown_tx,
+        ))
+    }
+
+    #[tokio::test]
+    async fn writer_serializes_and_publishes_event_for_each_message() {
+        let (client, mut server) = duplex(4096);
+        let (_msg_tx, msg_rx) = mpsc::unbounded_channel::<RawNetworkMessage>();
+        let (ev_tx, mut ev_rx) = mpsc::unbounded_channel::<Event>();
+        let (drain_done_tx, _drain_done_rx) = mpsc::channel::<()>(2);
+        let state = test_state(false);
+        let mut writer =
+            MessageWriterTask::new(client, msg_rx, ev_tx, drain_done_tx, &state.shutdown_tx);
+
+        writer.write(verack_message(Network::Bitcoin)).await.unwrap();
+
+        let ev = ev_rx.recv().await.unwrap();
+        assert_eq!(ev.name(), "verack");
+        assert!(matches!(ev.direction(), EventDirection::OUT));
+
+        let expected = serialize(&verack_message(Network::Bitcoin));
+        let mut buf = vec![0u8; expected.len()];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, expected);
+    }
+
+    #[tokio::test]
+    async fn writer_flushes_queued_messages_before_reporting_drained() {
+        let state = test_state(false);
+        let (client, mut server) = duplex(4096);
+        let (msg_tx, msg_rx) = mpsc::unbounded_channel::<RawNetworkMessage>();
+        let (ev_tx, mut ev_rx) = mpsc::unbounded_channel::<Event>();
+        let (drain_done_tx, mut drain_done_rx) = mpsc::channel::<()>(2);
+
+        let handle =
+            MessageWriterTask::new(client, msg_rx, ev_tx, drain_done_tx, &state.shutdown_tx).spawn();
+
+        msg_tx.send(getaddr_message(Network::Bitcoin)).unwrap();
+        state.shutdown_tx.send(ShutdownPhase::Draining).unwrap();
+
+        // The queued message must be flushed before drain_done is reported.
+        drain_done_rx.recv().await.unwrap();
+        let ev = ev_rx.recv().await.unwrap();
+        assert_eq!(ev.name(), "getaddr");
+
+        let expected = serialize(&getaddr_message(Network::Bitcoin));
+        let mut buf = vec![0u8; expected.len()];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, expected);
+
+        state.shutdown_tx.send(ShutdownPhase::Close).unwrap();
+        handle.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::{stream, StreamExt};
+
+use crate::chain;
+use crate::types::{CrawlParams, EventChain, HSError, HandshakeParams, HandshakeResult};
+
+/// The per-peer handshake results of the crawl, plus a deduplicated list of
+/// every peer address it saw: the seed and everything discovered via
+/// `addr`/`addrv2`, including peers that were never handshaked because the
+/// depth or `max_peers` budget ran out.
+pub struct CrawlReport {
+    pub results: Vec<HandshakeResult>,
+    pub visited: Vec<String>,
+}
+
+/// Breadth-first crawl starting at `params.seed`: each newly discovered peer
+/// (reported via `addr`/`addrv2` events) is handshaked in turn, up to
+/// `params.depth` BFS levels and `params.max_peers` total peers.
+pub async fn perform_crawl(params: CrawlParams) -> Result<CrawlReport, HSError> {
+    let visited: Arc<Mutex<HashSet<String>>> = Arc::new(Mutex::new(HashSet::new()));
+    visited.lock().unwrap().insert(params.seed.clone());
+
+    let mut frontier = vec![params.seed.clone()];
+    let mut results = Vec::new();
+    let mut depth = 0;
+
+    while !frontier.is_empty() && depth < params.depth {
+        let budget_left = params.max_peers.saturating_sub(results.len());
+        if budget_left == 0 {
+            break;
+        }
+        frontier.truncate(budget_left);
+
+        let user_agent = params.user_agent.clone();
+        let network = params.network;
+        let output = params.output.clone();
+        let dwell = Duration::from_millis(params.dwell_millis);
+        let batch: Vec<(String, Result<EventChain, HSError>)> =
+            stream::iter(frontier.drain(..).map(|address| {
+                let user_agent = user_agent.clone();
+                let output = output.clone();
+                async move {
+                    let peer_params = HandshakeParams {
+                        address: address.clone(),
+                        user_agent,
+                        network,
+                        output,
+                    };
+                    let res = chain::perform_btc_handshake_ex(peer_params, true, dwell).await;
+                    (address, res)
+                }
+            }))
+            .buffer_unordered(params.concurrency.max(1))
+            .collect()
+            .await;
+
+        let mut next_frontier = Vec::new();
+        for (address, res) in batch {
+            if let Ok(event_chain) = &res {
+                for peer in discovered_peers(event_chain) {
+                    if visited.lock().unwrap().insert(peer.clone()) {
+                        next_frontier.push(peer);
+                    }
+                }
+            }
+            results.push(HandshakeResult::new(address, res));
+        }
+
+        frontier = next_frontier;
+        depth += 1;
+    }
+
+    let visited = visited.lock().unwrap().iter().cloned().collect();
+    Ok(CrawlReport { results, visited })
+}
+
+/// Pulls every peer address an `addr`/`addrv2` event recorded during the handshake.
+fn discovered_peers(event_chain: &EventChain) -> Vec<String> {
+    let mut peers = Vec::new();
+    for i in 0..event_chain.len() {
+        if let Some(ev) = event_chain.get(i) {
+            if ev.name() == "addr" || ev.name() == "addrv2" {
+                if let Some((_, addr)) = ev.data_pairs().iter().find(|(k, _)| k == "address") {
+                    peers.push(addr.clone());
+                }
+            }
+        }
+    }
+    peers
+}
@@ -0,0 +1,41 @@
+use futures::{stream, StreamExt};
+
+use crate::chain;
+use crate::types::{FleetParams, FleetSummary, HSError, HandshakeParams, HandshakeResult};
+
+/// Drives `perform_btc_handshake` against every peer in `params`, bounded by
+/// `params.concurrency` simultaneous connections, and returns each peer's
+/// individual result alongside the aggregated fleet statistics.
+pub async fn perform_fleet_handshake(
+    params: FleetParams,
+) -> Result<(Vec<HandshakeResult>, FleetSummary), HSError> {
+    let peers = params.resolve_peers()?;
+    let concurrency = params.concurrency.max(1);
+    let user_agent = params.user_agent;
+    let network = params.network;
+    let output = params.output;
+
+    let results: Vec<HandshakeResult> = stream::iter(peers.into_iter().map(|address| {
+        let user_agent = user_agent.clone();
+        let output = output.clone();
+        async move {
+            let peer_params = HandshakeParams {
+                address: address.clone(),
+                user_agent,
+                network,
+                output,
+            };
+            let result = match tokio::spawn(chain::perform_btc_handshake(peer_params)).await {
+                Ok(res) => res,
+                Err(join_err) => Err(HSError::from(join_err)),
+            };
+            HandshakeResult::new(address, result)
+        }
+    }))
+    .buffer_unordered(concurrency)
+    .collect()
+    .await;
+
+    let summary = FleetSummary::from_results(&results);
+    Ok((results, summary))
+}
@@ -1,29 +1,90 @@
 mod types;
 mod chain;
+mod fleet;
+mod crawl;
+mod report;
+mod telemetry;
 use std::process::exit;
 use structopt::StructOpt;
-use types::{HandshakeParams, HandshakeResult, HSError};
+use types::{CrawlParams, FleetParams, HandshakeParams, HandshakeResult, HSError};
 
 
 pub async fn perform_handshake(params: HandshakeParams) -> Result<HandshakeResult, HSError> {
-    let join_handle = tokio::spawn(chain::perform_btc_handshake(params.clone())).await?;     
+    let join_handle = tokio::spawn(chain::perform_btc_handshake(params.clone())).await?;
     let res = HandshakeResult::new(params.address, join_handle);
     Ok(res)
 }
 
-
+#[derive(StructOpt, Debug)]
+enum Cli {
+    /// Handshake a single peer (the original behaviour).
+    Single(HandshakeParams),
+    /// Handshake many peers concurrently and report aggregated stats.
+    Fleet(FleetParams),
+    /// Crawl the peer graph from a seed node via `getaddr`/`addr`.
+    Crawl(CrawlParams),
+}
 
 #[tokio::main]
 async fn main() {
-    let config = HandshakeParams::from_args();
+    telemetry::init_tracing();
 
-    match perform_handshake(config).await {
-        Ok(handshake_result) =>  println!("{}", handshake_result),
-        Err(err) => {
-            println!("{}", err);
-            exit(1)
+    match Cli::from_args() {
+        Cli::Single(config) => {
+            let format = config.output.format;
+            match perform_handshake(config).await {
+                Ok(handshake_result) => {
+                    if let Ok(chain) = handshake_result.result() {
+                        telemetry::record_handshake_spans(chain);
+                    }
+                    report::print_single(&handshake_result, format);
+                }
+                Err(err) => {
+                    println!("{}", err);
+                    telemetry::shutdown_tracing();
+                    exit(1)
+                }
+            }
+        }
+        Cli::Fleet(config) => {
+            let format = config.output.format;
+            match fleet::perform_fleet_handshake(config).await {
+                Ok((results, summary)) => {
+                    for result in &results {
+                        if let Ok(chain) = result.result() {
+                            telemetry::record_handshake_spans(chain);
+                        }
+                    }
+                    report::print_fleet(&results, &summary, format);
+                }
+                Err(err) => {
+                    println!("{}", err);
+                    telemetry::shutdown_tracing();
+                    exit(1)
+                }
+            }
+        }
+        Cli::Crawl(config) => {
+            let format = config.output.format;
+            match crawl::perform_crawl(config).await {
+                Ok(crawl_report) => {
+                    for result in &crawl_report.results {
+                        if let Ok(chain) = result.result() {
+                            telemetry::record_handshake_spans(chain);
+                        }
+                    }
+                    report::print_crawl(&crawl_report, format);
+                }
+                Err(err) => {
+                    println!("{}", err);
+                    telemetry::shutdown_tracing();
+                    exit(1)
+                }
+            }
         }
     }
+
+    telemetry::shutdown_tracing();
 }
 
 
@@ -0,0 +1,206 @@
+//! Serializes handshake results for `--format json`/`--format ndjson`.
+//!
+//! `EventChain`/`Event` stay serde-free so the in-process event bookkeeping
+//! doesn't have to track wire-format concerns; this module mirrors them into
+//! dedicated record types instead.
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+use serde::Serialize;
+
+use crate::crawl::CrawlReport;
+use crate::types::{Event, EventChain, EventDirection, FleetSummary, HandshakeResult, OutputFormat};
+
+#[derive(Serialize)]
+struct EventRecord {
+    name: String,
+    direction: &'static str,
+    absolute_unix_ms: u128,
+    relative_ms: u128,
+    data: BTreeMap<String, String>,
+}
+
+impl EventRecord {
+    fn from_event(event: &Event, chain_start: SystemTime) -> Self {
+        EventRecord {
+            name: event.name().to_string(),
+            direction: match event.direction() {
+                EventDirection::IN => "in",
+                EventDirection::OUT => "out",
+            },
+            absolute_unix_ms: event
+                .wall_time()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+            relative_ms: event
+                .wall_time()
+                .duration_since(chain_start)
+                .unwrap_or_default()
+                .as_millis(),
+            data: event.data_pairs().iter().cloned().collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct EventChainRecord {
+    peer_id: String,
+    complete: bool,
+    events: Vec<EventRecord>,
+}
+
+impl EventChainRecord {
+    fn from_chain(chain: &EventChain) -> Self {
+        let chain_start = chain.get(0).map(Event::wall_time).unwrap_or_else(SystemTime::now);
+        EventChainRecord {
+            peer_id: chain.id().to_string(),
+            complete: chain.is_complete(),
+            events: (0..chain.len())
+                .filter_map(|i| chain.get(i))
+                .map(|ev| EventRecord::from_event(ev, chain_start))
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct HandshakeRecord {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chain: Option<EventChainRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl HandshakeRecord {
+    fn from_result(result: &HandshakeResult) -> Self {
+        match result.result() {
+            Ok(chain) => HandshakeRecord {
+                id: result.id().to_string(),
+                chain: Some(EventChainRecord::from_chain(chain)),
+                error: None,
+            },
+            Err(err) => HandshakeRecord {
+                id: result.id().to_string(),
+                chain: None,
+                error: Some(err.to_string()),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct FleetSummaryRecord {
+    total: usize,
+    successes: usize,
+    timeouts: usize,
+    connection_refused: usize,
+    other_errors: usize,
+    mean_latency_ms: Option<u128>,
+    p50_latency_ms: Option<u128>,
+    p95_latency_ms: Option<u128>,
+}
+
+impl FleetSummaryRecord {
+    fn from_summary(summary: &FleetSummary) -> Self {
+        FleetSummaryRecord {
+            total: summary.total,
+            successes: summary.successes,
+            timeouts: summary.timeouts,
+            connection_refused: summary.connection_refused,
+            other_errors: summary.other_errors,
+            mean_latency_ms: summary.mean_latency.map(|d| d.as_millis()),
+            p50_latency_ms: summary.p50_latency.map(|d| d.as_millis()),
+            p95_latency_ms: summary.p95_latency.map(|d| d.as_millis()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct FleetReportRecord {
+    results: Vec<HandshakeRecord>,
+    summary: FleetSummaryRecord,
+}
+
+#[derive(Serialize)]
+struct CrawlReportRecord {
+    results: Vec<HandshakeRecord>,
+    visited: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct VisitedRecord {
+    visited: Vec<String>,
+}
+
+pub fn print_single(result: &HandshakeResult, format: OutputFormat) {
+    match format {
+        OutputFormat::Pretty => println!("{}", result),
+        OutputFormat::Json | OutputFormat::Ndjson => {
+            print_json(&HandshakeRecord::from_result(result), format)
+        }
+    }
+}
+
+pub fn print_fleet(results: &[HandshakeResult], summary: &FleetSummary, format: OutputFormat) {
+    match format {
+        OutputFormat::Pretty => {
+            for result in results {
+                println!("{}", result);
+            }
+            println!("{}", summary);
+        }
+        OutputFormat::Ndjson => {
+            for result in results {
+                print_json(&HandshakeRecord::from_result(result), format);
+            }
+            print_json(&FleetSummaryRecord::from_summary(summary), format);
+        }
+        OutputFormat::Json => {
+            let report = FleetReportRecord {
+                results: results.iter().map(HandshakeRecord::from_result).collect(),
+                summary: FleetSummaryRecord::from_summary(summary),
+            };
+            print_json(&report, format);
+        }
+    }
+}
+
+pub fn print_crawl(report: &CrawlReport, format: OutputFormat) {
+    match format {
+        OutputFormat::Pretty => {
+            for result in &report.results {
+                println!("{}", result);
+            }
+            println!("discovered {} distinct peers", report.visited.len());
+            for peer in &report.visited {
+                println!("  {}", peer);
+            }
+        }
+        OutputFormat::Ndjson => {
+            for result in &report.results {
+                print_json(&HandshakeRecord::from_result(result), format);
+            }
+            print_json(&VisitedRecord { visited: report.visited.clone() }, format);
+        }
+        OutputFormat::Json => {
+            let record = CrawlReportRecord {
+                results: report.results.iter().map(HandshakeRecord::from_result).collect(),
+                visited: report.visited.clone(),
+            };
+            print_json(&record, format);
+        }
+    }
+}
+
+fn print_json<T: Serialize>(value: &T, format: OutputFormat) {
+    let rendered = match format {
+        OutputFormat::Json => serde_json::to_string_pretty(value),
+        _ => serde_json::to_string(value),
+    };
+    match rendered {
+        Ok(line) => println!("{}", line),
+        Err(err) => eprintln!("failed to serialize output: {}", err),
+    }
+}
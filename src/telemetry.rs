@@ -0,0 +1,105 @@
+//! Optional OpenTelemetry span export for completed handshakes.
+//!
+//! Disabled unless the `otel` feature is enabled, in which case `init_tracing`
+//! installs a batch OTLP exporter pointed at `OTEL_EXPORTER_OTLP_ENDPOINT`
+//! (falling back to the default local collector address if unset), and
+//! `record_handshake_spans` opens one span per handshake with a child span
+//! for each version/verack exchange, timed from the wall-clock gaps
+//! `EventChain` already recorded. Callers must invoke `shutdown_tracing`
+//! before the process exits so the exporter has a chance to flush.
+use crate::types::EventChain;
+
+#[cfg(feature = "otel")]
+mod otel {
+    use opentelemetry::{
+        global,
+        trace::{Span, TraceContextExt, Tracer},
+        Context, KeyValue,
+    };
+    use opentelemetry_otlp::WithExportConfig;
+
+    use crate::types::{Event, EventChain, EventDirection};
+
+    const TRACED_MESSAGES: &[&str] = &["version", "verack"];
+    const DEFAULT_ENDPOINT: &str = "http://localhost:4317";
+
+    /// Installs a batch OTLP exporter as the global tracer provider. Without
+    /// this, `global::tracer(...)` silently resolves to a no-op tracer and
+    /// every span `record` builds is created and immediately discarded.
+    pub fn init() {
+        let endpoint = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+            .unwrap_or_else(|_| DEFAULT_ENDPOINT.to_string());
+
+        let result = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .install_batch(opentelemetry_sdk::runtime::Tokio);
+
+        if let Err(err) = result {
+            eprintln!("⚠️  failed to install OTLP tracer, spans will not be exported: {}", err);
+        }
+    }
+
+    pub fn shutdown() {
+        global::shutdown_tracer_provider();
+    }
+
+    pub fn record(chain: &EventChain) {
+        let tracer = global::tracer("btc-handshake");
+        let mut span = tracer.start(format!("handshake/{}", chain.id()));
+        span.set_attribute(KeyValue::new("peer.id", chain.id().to_string()));
+        span.set_attribute(KeyValue::new("handshake.complete", chain.is_complete()));
+        // Parent children off the span's own context rather than handing the
+        // owned `span` to `Context`, which would take ownership of it and
+        // leave us without a way to call `.end()` on it directly below.
+        let parent_cx = Context::new().with_remote_span_context(span.span_context().clone());
+
+        let mut previous: Option<&Event> = None;
+        for i in 0..chain.len() {
+            let Some(event) = chain.get(i) else { continue };
+            if TRACED_MESSAGES.contains(&event.name()) {
+                let label = match event.direction() {
+                    EventDirection::OUT => format!("{}-out", event.name()),
+                    EventDirection::IN => format!("{}-in", event.name()),
+                };
+                let start = previous.map(Event::wall_time).unwrap_or_else(|| event.wall_time());
+                let mut child = tracer
+                    .span_builder(label)
+                    .with_start_time(start)
+                    .with_end_time(event.wall_time())
+                    .start_with_context(&tracer, &parent_cx);
+                child.end();
+            }
+            previous = Some(event);
+        }
+
+        span.end();
+    }
+}
+
+/// No-op unless built with the `otel` feature. Call once at startup, before
+/// any handshake runs.
+pub fn init_tracing() {
+    #[cfg(feature = "otel")]
+    otel::init();
+}
+
+/// No-op unless built with the `otel` feature. Call once before the process
+/// exits so batched spans are flushed to the collector.
+pub fn shutdown_tracing() {
+    #[cfg(feature = "otel")]
+    otel::shutdown();
+}
+
+/// No-op unless built with the `otel` feature, so call sites don't need to
+/// know whether OpenTelemetry support was compiled in.
+pub fn record_handshake_spans(chain: &EventChain) {
+    #[cfg(feature = "otel")]
+    otel::record(chain);
+    #[cfg(not(feature = "otel"))]
+    let _ = chain;
+}
@@ -2,7 +2,8 @@ use std::fmt::{self, Display};
 
 use std::{
     ops::Add,
-    time::{Duration, Instant},
+    str::FromStr,
+    time::{Duration, Instant, SystemTime},
 };
 use tokio::{
     sync::{broadcast::error::RecvError, mpsc::error::SendError},
@@ -12,6 +13,8 @@ use tokio::{
 
 use structopt::StructOpt;
 
+use bitcoin::Network;
+
 pub const HS_OK: &str = "🟩";
 pub const HS_NOK: &str = "🔴";
 pub const HS_IN: &str = "<<<<";
@@ -26,6 +29,151 @@ pub struct HandshakeParams {
   pub address: String,
   #[structopt(short, long, help = "The user agent of the BTC node ")]
   pub user_agent: String,
+  #[structopt(
+      short,
+      long,
+      default_value = "bitcoin",
+      help = "Bitcoin network to speak to the peer in (bitcoin, testnet, signet, regtest)."
+  )]
+  pub network: Network,
+  #[structopt(flatten)]
+  pub output: OutputParams,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct FleetParams {
+  #[structopt(short, long, help = "The user agent advertised to every peer.")]
+  pub user_agent: String,
+  #[structopt(
+      long,
+      help = "Comma separated list of peer addresses, e.g. 1.2.3.4:8333,5.6.7.8:8333."
+  )]
+  pub peers: Option<String>,
+  #[structopt(
+      long,
+      parse(from_os_str),
+      help = "Path to a file with one peer address per line."
+  )]
+  pub peers_file: Option<std::path::PathBuf>,
+  #[structopt(
+      long,
+      default_value = "8",
+      help = "Maximum number of handshakes to run concurrently."
+  )]
+  pub concurrency: usize,
+  #[structopt(
+      short,
+      long,
+      default_value = "bitcoin",
+      help = "Bitcoin network to speak to every peer in (bitcoin, testnet, signet, regtest)."
+  )]
+  pub network: Network,
+  #[structopt(flatten)]
+  pub output: OutputParams,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub struct CrawlParams {
+  #[structopt(short, long, help = "Seed peer to start crawling from, e.g. 1.2.3.4:8333.")]
+  pub seed: String,
+  #[structopt(short, long, help = "The user agent advertised to every peer.")]
+  pub user_agent: String,
+  #[structopt(long, default_value = "3", help = "Maximum BFS depth to crawl from the seed.")]
+  pub depth: usize,
+  #[structopt(long, default_value = "256", help = "Maximum total number of peers to visit.")]
+  pub max_peers: usize,
+  #[structopt(
+      long,
+      default_value = "8",
+      help = "Maximum number of handshakes to run concurrently at each depth level."
+  )]
+  pub concurrency: usize,
+  #[structopt(
+      short,
+      long,
+      default_value = "bitcoin",
+      help = "Bitcoin network to speak to every peer in (bitcoin, testnet, signet, regtest)."
+  )]
+  pub network: Network,
+  #[structopt(
+      long,
+      default_value = "5000",
+      help = "How long to keep each connection open, counted from when the version message is sent (so including the handshake itself), waiting for a getaddr reply, in milliseconds."
+  )]
+  pub dwell_millis: u64,
+  #[structopt(flatten)]
+  pub output: OutputParams,
+}
+
+/// Shared by every subcommand via `#[structopt(flatten)]` so `--format` means
+/// the same thing everywhere: how results are printed once the handshake(s)
+/// finish, independent of what's being run.
+#[derive(StructOpt, Debug, Clone)]
+pub struct OutputParams {
+  #[structopt(
+      long,
+      default_value = "pretty",
+      help = "Output format: pretty, json, or ndjson."
+  )]
+  pub format: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Pretty,
+    Json,
+    Ndjson,
+}
+
+impl FromStr for OutputFormat {
+    type Err = HSError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pretty" => Ok(OutputFormat::Pretty),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            other => Err(HSError {
+                err_message: format!(
+                    "unknown output format '{}': expected pretty, json, or ndjson",
+                    other
+                ),
+            }),
+        }
+    }
+}
+
+impl FleetParams {
+    /// Merges `--peers` and `--peers-file` into a single, order-preserving address list.
+    pub fn resolve_peers(&self) -> Result<Vec<String>, HSError> {
+        let mut peers = Vec::new();
+
+        if let Some(list) = &self.peers {
+            peers.extend(
+                list.split(',')
+                    .map(|addr| addr.trim().to_string())
+                    .filter(|addr| !addr.is_empty()),
+            );
+        }
+
+        if let Some(path) = &self.peers_file {
+            let content = std::fs::read_to_string(path)?;
+            peers.extend(
+                content
+                    .lines()
+                    .map(|addr| addr.trim().to_string())
+                    .filter(|addr| !addr.is_empty()),
+            );
+        }
+
+        if peers.is_empty() {
+            return Err(HSError {
+                err_message: "no peers provided: use --peers or --peers-file".to_string(),
+            });
+        }
+
+        Ok(peers)
+    }
 }
 
 #[derive(Debug)]
@@ -116,6 +264,90 @@ impl HandshakeResult {
     }
 }
 
+pub struct FleetSummary {
+    pub total: usize,
+    pub successes: usize,
+    pub timeouts: usize,
+    pub connection_refused: usize,
+    pub other_errors: usize,
+    pub mean_latency: Option<Duration>,
+    pub p50_latency: Option<Duration>,
+    pub p95_latency: Option<Duration>,
+}
+
+impl FleetSummary {
+    pub fn from_results(results: &[HandshakeResult]) -> FleetSummary {
+        let mut successes = 0;
+        let mut timeouts = 0;
+        let mut connection_refused = 0;
+        let mut other_errors = 0;
+        let mut latencies: Vec<Duration> = Vec::new();
+
+        for res in results {
+            match res.result() {
+                Ok(chain) => {
+                    successes += 1;
+                    latencies.push(chain.total_time());
+                }
+                Err(err) if err.err_message.contains("refused") => connection_refused += 1,
+                Err(err) if err.err_message.contains("elapsed") || err.err_message.contains("timed out") => {
+                    timeouts += 1
+                }
+                Err(_) => other_errors += 1,
+            }
+        }
+
+        latencies.sort();
+
+        FleetSummary {
+            total: results.len(),
+            successes,
+            timeouts,
+            connection_refused,
+            other_errors,
+            mean_latency: mean(&latencies),
+            p50_latency: percentile(&latencies, 0.50),
+            p95_latency: percentile(&latencies, 0.95),
+        }
+    }
+}
+
+fn mean(samples: &[Duration]) -> Option<Duration> {
+    if samples.is_empty() {
+        return None;
+    }
+    let total: Duration = samples.iter().sum();
+    Some(total / samples.len() as u32)
+}
+
+fn percentile(sorted_samples: &[Duration], pct: f64) -> Option<Duration> {
+    if sorted_samples.is_empty() {
+        return None;
+    }
+    let idx = (((sorted_samples.len() - 1) as f64) * pct).round() as usize;
+    sorted_samples.get(idx).copied()
+}
+
+impl Display for FleetSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}/{} ok, {} timed out, {} refused, {} other errors",
+            HS_OK, self.successes, self.total, self.timeouts, self.connection_refused, self.other_errors
+        )?;
+        if let Some(mean) = self.mean_latency {
+            write!(f, " || mean {:#?}", mean)?;
+        }
+        if let Some(p50) = self.p50_latency {
+            write!(f, ", p50 {:#?}", p50)?;
+        }
+        if let Some(p95) = self.p95_latency {
+            write!(f, ", p95 {:#?}", p95)?;
+        }
+        Ok(())
+    }
+}
+
 impl Display for HandshakeResult {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self.result.is_ok() {
@@ -179,6 +411,15 @@ impl EventChain {
     pub fn id(&self) -> &str {
         self.id.as_ref()
     }
+
+    /// Time spanned by the recorded events, start to finish, measured on the
+    /// monotonic clock (`Event::time`), not `wall_time`.
+    pub fn total_time(&self) -> Duration {
+        match (self.events.first(), self.events.last()) {
+            (Some(first), Some(last)) => last.time().duration_since(first.time()),
+            _ => Duration::from_millis(0),
+        }
+    }
 }
 
 impl Display for EventChain {
@@ -212,6 +453,7 @@ impl Display for EventChain {
 pub struct Event {
     name: String,
     time: Instant,
+    wall_time: SystemTime,
     direction: EventDirection,
     data_pairs: Vec<(String, String)>,
 }
@@ -222,6 +464,7 @@ impl Event {
             name,
             direction,
             time: Instant::now(),
+            wall_time: SystemTime::now(),
             data_pairs: Vec::new(),
         }
     }
@@ -234,6 +477,12 @@ impl Event {
         self.time
     }
 
+    /// Wall-clock counterpart of `time()`: `Instant` has no epoch, so this is
+    /// what structured output serializes as the event's absolute timestamp.
+    pub fn wall_time(&self) -> SystemTime {
+        self.wall_time
+    }
+
     pub fn direction(&self) -> &EventDirection {
         &self.direction
     }
@@ -264,6 +513,9 @@ impl Display for Event {
     }
 }
 
+// `IN`/`OUT` read better here than `In`/`Out` given the wire-protocol framing
+// (`version-in`, `version-out`) used for span names in `telemetry.rs`.
+#[allow(clippy::upper_case_acronyms)]
 pub enum EventDirection {
     IN,
     OUT,